@@ -1,37 +1,226 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 
+use chrono::NaiveDate;
 use futures_util::future::join_all;
-use serde::Deserialize;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
 use tokio::{
     fs::{File, OpenOptions},
     io::AsyncWriteExt,
 };
 
+use crate::metrics::Metrics;
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct GhostConfig {
     pub(crate) channels: Vec<String>,
     pub(crate) log_path: String,
+    #[serde(default)]
+    pub(crate) log_format: LogFormat,
+    /// Maps a bare channel name (as in `channels`) to the Discord webhook URL
+    /// that channel's chat should be mirrored to.
+    #[serde(default)]
+    pub(crate) discord_webhooks: HashMap<String, String>,
+    /// Rotate a channel's log file once it exceeds this many bytes, in
+    /// addition to the daily rotation `FileHandleManager` always does.
+    #[serde(default)]
+    pub(crate) max_bytes: Option<u64>,
+}
+
+/// The on-disk encoding used for log lines.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    #[default]
+    Ron,
+    Jsonl,
+}
+
+/// A single logged event, normalized across every Twitch command we handle so
+/// there is one serialization path regardless of `LogFormat`.
+///
+/// `Serialize` is implemented by hand below instead of deriving it: the
+/// natural derive shape pairs `#[serde(flatten)]` on `kind` with an
+/// internally-tagged `LogEventKind`, and that combination forces serde's
+/// buffered map/content path, which `ron` has historically not handled for
+/// every shape. Writing the map out field-by-field sidesteps that path
+/// entirely while keeping the same `channel`/`timestamp`/`type`/... keys on
+/// the wire.
+#[derive(Debug)]
+pub(crate) struct LogEvent<'a> {
+    pub(crate) channel: &'a str,
+    pub(crate) timestamp: String,
+    pub(crate) kind: LogEventKind<'a>,
+}
+
+impl<'a> Serialize for LogEvent<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("channel", self.channel)?;
+        map.serialize_entry("timestamp", &self.timestamp)?;
+        map.serialize_entry("type", self.kind.type_name())?;
+        match &self.kind {
+            LogEventKind::PrivMsg { sender, message, tags } => {
+                map.serialize_entry("sender", sender)?;
+                map.serialize_entry("message", message)?;
+                map.serialize_entry("tags", tags)?;
+            }
+            LogEventKind::ClearChat { target, tags } => {
+                map.serialize_entry("target", target)?;
+                map.serialize_entry("tags", tags)?;
+            }
+            LogEventKind::ClearMsg { message, tags } => {
+                map.serialize_entry("message", message)?;
+                map.serialize_entry("tags", tags)?;
+            }
+            LogEventKind::RoomState { tags } => {
+                map.serialize_entry("tags", tags)?;
+            }
+            LogEventKind::UserNotice { tags } => {
+                map.serialize_entry("tags", tags)?;
+            }
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum LogEventKind<'a> {
+    PrivMsg {
+        sender: &'a str,
+        message: &'a str,
+        tags: BTreeMap<String, String>,
+    },
+    ClearChat {
+        target: Option<&'a str>,
+        tags: BTreeMap<String, String>,
+    },
+    ClearMsg {
+        message: &'a str,
+        tags: BTreeMap<String, String>,
+    },
+    RoomState { tags: BTreeMap<String, String> },
+    UserNotice { tags: BTreeMap<String, String> },
+}
+
+impl<'a> LogEventKind<'a> {
+    fn type_name(&self) -> &'static str {
+        match self {
+            LogEventKind::PrivMsg { .. } => "PRIVMSG",
+            LogEventKind::ClearChat { .. } => "CLEARCHAT",
+            LogEventKind::ClearMsg { .. } => "CLEARMSG",
+            LogEventKind::RoomState { .. } => "ROOMSTATE",
+            LogEventKind::UserNotice { .. } => "USERNOTICE",
+        }
+    }
+}
+
+/// An open log file together with the bookkeeping needed to know when it's
+/// due for rotation.
+struct LogFile {
+    file: File,
+    date: NaiveDate,
+    /// Which same-day size rotation this is, starting at 0. Bumped (rather
+    /// than reset) by a `max_bytes` rotation so it names a genuinely new
+    /// file instead of reopening the one that just hit the limit; reset to
+    /// 0 when `date` rolls over.
+    segment: u32,
+    bytes_written: u64,
+}
+
+pub(crate) struct FileHandleManager {
+    files: HashMap<String, LogFile>,
+    format: LogFormat,
+    metrics: Metrics,
+    log_path: PathBuf,
+    max_bytes: Option<u64>,
 }
-pub(crate) struct FileHandleManager(pub(crate) HashMap<String, File>);
 
 impl FileHandleManager {
-    pub async fn write_to_log(&mut self, channel_name: &String, line: String) {
-        match self.0.get_mut(channel_name) {
-            Some(file) => file
-                .write_all(
-                    format!("{} // {}\n", line, chrono::Local::now().to_rfc3339()).as_bytes(),
-                )
-                .await
-                .expect("append to file"),
+    pub async fn write_to_log(&mut self, channel_name: &str, kind: LogEventKind<'_>) {
+        let event = LogEvent {
+            channel: channel_name,
+            timestamp: chrono::Local::now().to_rfc3339(),
+            kind,
+        };
+
+        let line = match self.format {
+            LogFormat::Ron => format!("{}\n", ron::to_string(&event).unwrap()),
+            LogFormat::Jsonl => format!("{}\n", serde_json::to_string(&event).unwrap()),
+        };
+
+        self.rotate_if_needed(channel_name).await;
+
+        match self.files.get_mut(channel_name) {
+            Some(log_file) => {
+                log_file
+                    .file
+                    .write_all(line.as_bytes())
+                    .await
+                    .expect("append to file");
+                log_file.bytes_written += line.len() as u64;
+                self.metrics
+                    .bytes_written
+                    .with_label_values(&[channel_name])
+                    .inc_by(line.len() as u64);
+            }
+            None => eprintln!(
+                "No file opened for {}, would have logged:\n{:?}",
+                channel_name, event
+            ),
+        }
+    }
+
+    /// Writes a single raw line verbatim, without going through the configured
+    /// `LogFormat`. Used for marker lines such as reconnect notices.
+    pub async fn write_marker(&mut self, channel_name: &str, line: String) {
+        self.rotate_if_needed(channel_name).await;
+
+        match self.files.get_mut(channel_name) {
+            Some(log_file) => {
+                let line = format!("{}\n", line);
+                log_file
+                    .file
+                    .write_all(line.as_bytes())
+                    .await
+                    .expect("append to file");
+                log_file.bytes_written += line.len() as u64;
+            }
             None => eprintln!(
                 "No file opened for {}, would have logged:\n{:?}",
                 channel_name, line
             ),
         }
     }
+
+    /// Transparently reopens a channel's log file when the local date has
+    /// rolled over, or once it exceeds `max_bytes`, closing the old handle.
+    async fn rotate_if_needed(&mut self, channel_name: &str) {
+        let today = chrono::Local::now().date_naive();
+
+        let segment = match self.files.get(channel_name) {
+            Some(log_file) if log_file.date != today => Some(0),
+            Some(log_file)
+                if self
+                    .max_bytes
+                    .is_some_and(|max_bytes| log_file.bytes_written >= max_bytes) =>
+            {
+                Some(log_file.segment + 1)
+            }
+            _ => None,
+        };
+
+        if let Some(segment) = segment {
+            let log_file = open_channel_log(&self.log_path, channel_name, today, segment).await;
+            self.files.insert(channel_name.to_owned(), log_file);
+        }
+    }
 }
 
-pub(crate) async fn load_config() -> (Vec<String>, FileHandleManager) {
+pub(crate) async fn load_config(
+    metrics: &Metrics,
+) -> (Vec<String>, FileHandleManager, HashMap<String, String>) {
     let ghost_config = ron::from_str::<GhostConfig>(
         &tokio::fs::read_to_string("config.ron")
             .await
@@ -50,33 +239,94 @@ pub(crate) async fn load_config() -> (Vec<String>, FileHandleManager) {
         })
         .collect();
 
-    tokio::fs::create_dir_all(ghost_config.log_path)
+    let discord_webhooks: HashMap<String, String> = ghost_config
+        .discord_webhooks
+        .into_iter()
+        .map(|(channel, webhook_url)| (format!("#{}", channel.to_ascii_lowercase()), webhook_url))
+        .collect();
+
+    let log_path = PathBuf::from(ghost_config.log_path);
+
+    tokio::fs::create_dir_all(&log_path)
         .await
         .expect("create log directory");
 
-    let file_handles = open_log_files(&irc_channels.clone()).await;
-    (irc_channels, file_handles)
+    metrics.connected_channels.set(irc_channels.len() as i64);
+
+    let file_handles = open_log_files(
+        &irc_channels.clone(),
+        &log_path,
+        ghost_config.log_format,
+        ghost_config.max_bytes,
+        metrics.clone(),
+    )
+    .await;
+    (irc_channels, file_handles, discord_webhooks)
 }
 
-async fn open_log_files(irc_channels: &[String]) -> FileHandleManager {
-    let startup_time = chrono::Local::now().to_rfc3339();
-
-    FileHandleManager(
-        join_all(irc_channels.iter().map(|c| async {
-            let c = c.to_owned();
-            let mut file = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(format!("logs/{}.txt", c))
-                .await
-                .expect("open/create log file");
-            file.write_all(format!("// File opened at {}\n", startup_time).as_bytes())
-                .await
-                .expect("write initial line");
-            (c, file)
-        }))
+async fn open_log_files(
+    irc_channels: &[String],
+    log_path: &Path,
+    format: LogFormat,
+    max_bytes: Option<u64>,
+    metrics: Metrics,
+) -> FileHandleManager {
+    let today = chrono::Local::now().date_naive();
+
+    let files = join_all(irc_channels.iter().map(|c| async move {
+        (c.to_owned(), open_channel_log(log_path, c, today, 0).await)
+    }))
+    .await
+    .into_iter()
+    .collect();
+
+    FileHandleManager {
+        files,
+        format,
+        metrics,
+        log_path: log_path.to_owned(),
+        max_bytes,
+    }
+}
+
+/// Opens (creating if necessary) `{log_path}/{channel}/{date}.txt` for
+/// `segment` 0, or `{log_path}/{channel}/{date}.{segment}.txt` for a
+/// same-day size rotation, and writes the "file opened" header line.
+async fn open_channel_log(
+    log_path: &Path,
+    channel_name: &str,
+    date: NaiveDate,
+    segment: u32,
+) -> LogFile {
+    let channel_dir = log_path.join(channel_name);
+    tokio::fs::create_dir_all(&channel_dir)
         .await
-        .into_iter()
-        .collect(),
-    )
+        .expect("create channel log directory");
+
+    let file_name = if segment == 0 {
+        format!("{}.txt", date)
+    } else {
+        format!("{}.{}.txt", date, segment)
+    };
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(channel_dir.join(file_name))
+        .await
+        .expect("open/create log file");
+
+    let existing_bytes = file.metadata().await.expect("stat log file").len();
+
+    let header = format!("// File opened at {}\n", chrono::Local::now().to_rfc3339());
+    file.write_all(header.as_bytes())
+        .await
+        .expect("write initial line");
+
+    LogFile {
+        file,
+        date,
+        segment,
+        bytes_written: existing_bytes + header.len() as u64,
+    }
 }
@@ -0,0 +1,199 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tokio::time::{interval, sleep};
+
+/// How often newly mirrored messages are picked up and coalesced for
+/// sending.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Gap between consecutive POSTs to the same webhook within one flush.
+/// Draining a backlog all at once would still blow through Discord's
+/// per-webhook rate limit even if it's spread across flushes, so sends within
+/// a flush are paced too. The first POST of a flush is not delayed.
+const POST_PACING: Duration = Duration::from_millis(750);
+
+/// Maximum POSTs sent to a single webhook per flush. Anything left over
+/// stays queued for a later flush instead of pacing one busy webhook's
+/// backlog at the expense of every other webhook's timeliness.
+const MAX_POSTS_PER_FLUSH: usize = 4;
+
+/// Maximum messages held per webhook until they're coalesced. Past this, the oldest
+/// queued messages are dropped and replaced with a summary note, so a
+/// webhook that's falling behind can't grow memory without bound.
+const MAX_QUEUED_PER_WEBHOOK: usize = 200;
+
+#[derive(Debug, Serialize)]
+struct WebhookPayload {
+    username: String,
+    content: String,
+}
+
+struct QueuedMessage {
+    username: String,
+    content: String,
+}
+
+#[derive(Default)]
+struct WebhookQueue {
+    messages: VecDeque<QueuedMessage>,
+    dropped: usize,
+}
+
+/// Mirrors Twitch PRIVMSGs to per-channel Discord webhooks. Callers hand
+/// messages to `mirror`, which appends them to a per-webhook queue; a
+/// background task periodically coalesces each queue's backlog (consecutive
+/// same-sender messages are joined into a single POST) and sends it paced
+/// and capped per webhook, so Discord's per-webhook rate limit holds
+/// regardless of how bursty the backlog is.
+#[derive(Clone)]
+pub(crate) struct DiscordBridge {
+    queues: Arc<Mutex<HashMap<String, WebhookQueue>>>,
+}
+
+impl DiscordBridge {
+    pub(crate) fn spawn() -> DiscordBridge {
+        let queues: Arc<Mutex<HashMap<String, WebhookQueue>>> = Arc::new(Mutex::new(HashMap::new()));
+        let client = reqwest::Client::new();
+
+        let bridge = DiscordBridge {
+            queues: queues.clone(),
+        };
+
+        tokio::spawn(async move {
+            let mut ticker = interval(FLUSH_INTERVAL);
+            let mut pending: HashMap<String, VecDeque<WebhookPayload>> = HashMap::new();
+
+            loop {
+                ticker.tick().await;
+
+                let due: Vec<(String, WebhookQueue)> = {
+                    let mut queues = queues.lock().await;
+                    // A webhook still sending an earlier backlog keeps its new
+                    // messages here, capped at `MAX_QUEUED_PER_WEBHOOK`.
+                    queues
+                        .iter_mut()
+                        .filter(|(webhook_url, queue)| {
+                            !queue.messages.is_empty() && !pending.contains_key(*webhook_url)
+                        })
+                        .map(|(webhook_url, queue)| (webhook_url.clone(), std::mem::take(queue)))
+                        .collect()
+                };
+
+                for (webhook_url, queue) in due {
+                    pending
+                        .entry(webhook_url)
+                        .or_default()
+                        .extend(coalesce(queue));
+                }
+
+                for (webhook_url, payloads) in pending.iter_mut() {
+                    send_some(&client, webhook_url, payloads).await;
+                }
+
+                pending.retain(|_, payloads| !payloads.is_empty());
+            }
+        });
+
+        bridge
+    }
+
+    pub(crate) async fn mirror(&self, webhook_url: &str, username: &str, content: &str) {
+        let mut queues = self.queues.lock().await;
+        let queue = queues.entry(webhook_url.to_owned()).or_default();
+
+        if queue.messages.len() >= MAX_QUEUED_PER_WEBHOOK {
+            queue.messages.pop_front();
+            queue.dropped += 1;
+        }
+
+        queue.messages.push_back(QueuedMessage {
+            username: username.to_owned(),
+            content: content.to_owned(),
+        });
+    }
+}
+
+/// Sends up to `MAX_POSTS_PER_FLUSH` queued payloads for one webhook, paced
+/// `POST_PACING` apart, popping each one off only once it's been sent. Stops
+/// early on a 429 or a request error, leaving the rest (including the one
+/// that was rejected) queued for the next flush instead of hammering a
+/// webhook that's already being rate-limited.
+async fn send_some(
+    client: &reqwest::Client,
+    webhook_url: &str,
+    payloads: &mut VecDeque<WebhookPayload>,
+) {
+    for sent in 0..MAX_POSTS_PER_FLUSH {
+        let Some(payload) = payloads.front() else {
+            break;
+        };
+        if sent > 0 {
+            sleep(POST_PACING).await;
+        }
+
+        let response = match client.post(webhook_url).json(payload).send().await {
+            Ok(response) => response,
+            Err(err) => {
+                eprintln!("Failed to forward message to Discord: {}", err);
+                break;
+            }
+        };
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            eprintln!("Discord webhook {} rate-limited, backing off", webhook_url);
+            break;
+        }
+
+        if let Err(err) = response.error_for_status() {
+            eprintln!("Discord webhook returned an error: {}", err);
+        }
+
+        payloads.pop_front();
+    }
+}
+
+/// Flattens a webhook's queued messages into one payload per sender run
+/// (consecutive messages from the same sender get joined with newlines into
+/// a single POST), with a leading summary payload if any were dropped for
+/// overflow.
+fn coalesce(queue: WebhookQueue) -> Vec<WebhookPayload> {
+    let mut payloads = Vec::new();
+
+    if queue.dropped > 0 {
+        payloads.push(WebhookPayload {
+            username: "purple-ghost".to_owned(),
+            content: format!(
+                "_{} message(s) dropped, webhook falling behind_",
+                queue.dropped
+            ),
+        });
+    }
+
+    let mut messages = queue.messages.into_iter();
+    let Some(first) = messages.next() else {
+        return payloads;
+    };
+
+    let mut username = first.username;
+    let mut content = first.content;
+
+    for message in messages {
+        if message.username == username {
+            content.push('\n');
+            content.push_str(&message.content);
+        } else {
+            payloads.push(WebhookPayload { username, content });
+            username = message.username;
+            content = message.content;
+        }
+    }
+
+    payloads.push(WebhookPayload { username, content });
+
+    payloads
+}
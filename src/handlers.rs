@@ -1,122 +1,278 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
-use irc::proto::{message::Tag, Message};
-use serde::Serialize;
+use async_trait::async_trait;
+use irc::proto::{message::Tag, Command, Message};
 
-use crate::config::FileHandleManager;
+use crate::config::{FileHandleManager, LogEventKind};
+use crate::discord::DiscordBridge;
+use crate::metrics::Metrics;
 
-#[derive(Debug, Serialize)]
-struct PrivMsg<'a> {
-    sender: &'a str,
-    message: &'a String,
-    tags: BTreeMap<String, String>,
+/// What the main loop should do after a handler has run.
+pub(crate) enum EventAction {
+    Continue,
+    Reconnect,
 }
 
-pub(crate) async fn handle_priv_msg(
-    file_handles: &mut FileHandleManager,
-    sender: &str,
-    channel_name: &String,
-    message: &String,
-    tags: Option<Vec<Tag>>,
-) {
-    let priv_msg = PrivMsg {
-        sender,
-        message,
-        tags: tags_to_map(tags),
-    };
-
-    file_handles
-        .write_to_log(
-            channel_name,
-            format!("PRIVMSG{}", ron::to_string(&priv_msg).unwrap()),
-        )
-        .await;
+/// Shared state a `Handler` needs to do its work, rebuilt for every event.
+pub(crate) struct HandlerCtx<'a> {
+    pub(crate) file_handles: &'a mut FileHandleManager,
+    pub(crate) discord_webhooks: &'a HashMap<String, String>,
+    pub(crate) discord_bridge: &'a DiscordBridge,
+    pub(crate) metrics: &'a Metrics,
+    pub(crate) tags: BTreeMap<String, String>,
 }
 
-pub(crate) async fn handle_clear_chat(
-    file_handles: &mut FileHandleManager,
-    value: &[String],
-    tags: Option<Vec<Tag>>,
-) {
-    match value.len() {
-        1 => {
-            file_handles
-                .write_to_log(
-                    value.get(0).unwrap(),
-                    format!(
-                        "CLEARCHAT(tags:{})",
-                        ron::to_string(&tags_to_map(tags)).unwrap()
-                    ),
-                )
-                .await;
+impl<'a> HandlerCtx<'a> {
+    fn new(
+        file_handles: &'a mut FileHandleManager,
+        discord_webhooks: &'a HashMap<String, String>,
+        discord_bridge: &'a DiscordBridge,
+        metrics: &'a Metrics,
+        tags: Option<Vec<Tag>>,
+    ) -> HandlerCtx<'a> {
+        HandlerCtx {
+            file_handles,
+            discord_webhooks,
+            discord_bridge,
+            metrics,
+            tags: tags_to_map(tags),
         }
-        2 => {
-            file_handles
-                .write_to_log(
-                    value.get(0).unwrap(),
-                    format!(
-                        "CLEARCHAT(user:\"{}\",tags:{})",
-                        value.get(1).unwrap(),
-                        ron::to_string(&tags_to_map(tags)).unwrap()
-                    ),
-                )
-                .await;
+    }
+}
+
+/// Something that can react to one kind of Twitch IRC message. New message
+/// types (USERSTATE, GLOBALUSERSTATE, HOSTTARGET, WHISPER, NOTICE, ...) are
+/// added by implementing this trait and registering an instance in
+/// `HandlerRegistry::new`, rather than editing a central match.
+#[async_trait]
+pub(crate) trait Handler {
+    async fn handle(&self, ctx: &mut HandlerCtx<'_>, message: &Message) -> EventAction;
+}
+
+/// Maps Twitch command names to the handler that deals with them, falling
+/// back to a default diagnostic handler for anything unregistered.
+pub(crate) struct HandlerRegistry {
+    handlers: HashMap<&'static str, Box<dyn Handler + Send + Sync>>,
+    default: Box<dyn Handler + Send + Sync>,
+}
+
+impl HandlerRegistry {
+    pub(crate) fn new() -> HandlerRegistry {
+        let mut handlers: HashMap<&'static str, Box<dyn Handler + Send + Sync>> = HashMap::new();
+        handlers.insert("PRIVMSG", Box::new(PrivMsgHandler));
+        handlers.insert("CLEARCHAT", Box::new(ClearChatHandler));
+        handlers.insert("CLEARMSG", Box::new(ClearMsgHandler));
+        handlers.insert("ROOMSTATE", Box::new(NoticeHandler));
+        handlers.insert("USERNOTICE", Box::new(NoticeHandler));
+        handlers.insert("RECONNECT", Box::new(ReconnectHandler));
+
+        HandlerRegistry {
+            handlers,
+            default: Box::new(DefaultHandler),
         }
-        _ => {
-            panic!(
-                "unexpected number of params for CLEARCHAT: {}",
-                value.join(" ")
+    }
+
+    pub(crate) async fn dispatch(
+        &self,
+        file_handles: &mut FileHandleManager,
+        discord_webhooks: &HashMap<String, String>,
+        discord_bridge: &DiscordBridge,
+        metrics: &Metrics,
+        message: &Message,
+    ) -> EventAction {
+        let mut ctx = HandlerCtx::new(
+            file_handles,
+            discord_webhooks,
+            discord_bridge,
+            metrics,
+            message.tags.clone(),
+        );
+
+        let key = match &message.command {
+            Command::PRIVMSG(..) => "PRIVMSG",
+            Command::Raw(command, _) => command.as_str(),
+            _ => return self.default.handle(&mut ctx, message).await,
+        };
+
+        match self.handlers.get(key) {
+            Some(handler) => handler.handle(&mut ctx, message).await,
+            None => self.default.handle(&mut ctx, message).await,
+        }
+    }
+}
+
+struct PrivMsgHandler;
+
+#[async_trait]
+impl Handler for PrivMsgHandler {
+    async fn handle(&self, ctx: &mut HandlerCtx<'_>, message: &Message) -> EventAction {
+        let Command::PRIVMSG(channel_name, msg) = &message.command else {
+            return EventAction::Continue;
+        };
+        let sender = message.source_nickname().unwrap_or("???");
+
+        ctx.file_handles
+            .write_to_log(
+                channel_name,
+                LogEventKind::PrivMsg {
+                    sender,
+                    message: msg,
+                    tags: ctx.tags.clone(),
+                },
             )
+            .await;
+
+        ctx.metrics
+            .messages_logged
+            .with_label_values(&[channel_name.as_str()])
+            .inc();
+
+        if let Some(webhook_url) = ctx.discord_webhooks.get(channel_name) {
+            ctx.discord_bridge.mirror(webhook_url, sender, msg).await;
         }
+
+        EventAction::Continue
     }
 }
 
-pub(crate) async fn handle_clear_msg(
-    file_handles: &mut FileHandleManager,
-    value: &[String],
-    tags: Option<Vec<Tag>>,
-) {
-    file_handles
-        .write_to_log(
-            value.get(0).unwrap(),
-            format!(
-                "CLEARMSG(message:\"{}\",tags:{})",
-                value.get(1).unwrap(),
-                ron::to_string(&tags_to_map(tags)).unwrap()
-            ),
-        )
-        .await;
+struct ClearChatHandler;
+
+#[async_trait]
+impl Handler for ClearChatHandler {
+    async fn handle(&self, ctx: &mut HandlerCtx<'_>, message: &Message) -> EventAction {
+        let Command::Raw(_, value) = &message.command else {
+            return EventAction::Continue;
+        };
+
+        let channel_name = value.first().unwrap();
+        ctx.metrics
+            .clear_chats
+            .with_label_values(&[channel_name.as_str()])
+            .inc();
+
+        match value.len() {
+            1 => {
+                ctx.file_handles
+                    .write_to_log(
+                        channel_name,
+                        LogEventKind::ClearChat {
+                            target: None,
+                            tags: ctx.tags.clone(),
+                        },
+                    )
+                    .await;
+            }
+            2 => {
+                ctx.metrics
+                    .timeouts
+                    .with_label_values(&[channel_name.as_str()])
+                    .inc();
+                ctx.file_handles
+                    .write_to_log(
+                        channel_name,
+                        LogEventKind::ClearChat {
+                            target: Some(value.get(1).unwrap()),
+                            tags: ctx.tags.clone(),
+                        },
+                    )
+                    .await;
+            }
+            _ => {
+                panic!(
+                    "unexpected number of params for CLEARCHAT: {}",
+                    value.join(" ")
+                )
+            }
+        }
+
+        EventAction::Continue
+    }
+}
+
+struct ClearMsgHandler;
+
+#[async_trait]
+impl Handler for ClearMsgHandler {
+    async fn handle(&self, ctx: &mut HandlerCtx<'_>, message: &Message) -> EventAction {
+        let Command::Raw(_, value) = &message.command else {
+            return EventAction::Continue;
+        };
+
+        let channel_name = value.first().unwrap();
+        ctx.metrics
+            .clear_msgs
+            .with_label_values(&[channel_name.as_str()])
+            .inc();
+
+        ctx.file_handles
+            .write_to_log(
+                channel_name,
+                LogEventKind::ClearMsg {
+                    message: value.get(1).unwrap(),
+                    tags: ctx.tags.clone(),
+                },
+            )
+            .await;
+
+        EventAction::Continue
+    }
+}
+
+struct NoticeHandler;
+
+#[async_trait]
+impl Handler for NoticeHandler {
+    async fn handle(&self, ctx: &mut HandlerCtx<'_>, message: &Message) -> EventAction {
+        let Command::Raw(command, value) = &message.command else {
+            return EventAction::Continue;
+        };
+
+        let kind = match command.as_str() {
+            "ROOMSTATE" => LogEventKind::RoomState {
+                tags: ctx.tags.clone(),
+            },
+            "USERNOTICE" => LogEventKind::UserNotice {
+                tags: ctx.tags.clone(),
+            },
+            other => unreachable!("unexpected notice command: {other}"),
+        };
+
+        ctx.file_handles
+            .write_to_log(value.first().unwrap(), kind)
+            .await;
+
+        EventAction::Continue
+    }
 }
 
-pub(crate) async fn handle_notice(
-    file_handles: &mut FileHandleManager,
-    command: &String,
-    value: &[String],
-    tags: Option<Vec<Tag>>,
-) {
-    file_handles
-        .write_to_log(
-            value.get(0).unwrap(),
-            format!(
-                "{}(tags:{})",
-                command,
-                ron::to_string(&tags_to_map(tags)).unwrap()
-            ),
-        )
-        .await;
+struct ReconnectHandler;
+
+#[async_trait]
+impl Handler for ReconnectHandler {
+    async fn handle(&self, _ctx: &mut HandlerCtx<'_>, _message: &Message) -> EventAction {
+        EventAction::Reconnect
+    }
 }
 
-pub(crate) fn print_message(message: &Message) {
-    println!(
-        "{:?} {}{:?}",
-        message.command,
-        message
-            .prefix
-            .clone()
-            .map(|p| format!("from {:?} ", p))
-            .unwrap_or("".to_owned()),
-        tags_to_map(message.clone().tags),
-    )
+/// Falls back to printing the raw message for anything we don't have a
+/// dedicated handler for.
+struct DefaultHandler;
+
+#[async_trait]
+impl Handler for DefaultHandler {
+    async fn handle(&self, ctx: &mut HandlerCtx<'_>, message: &Message) -> EventAction {
+        println!(
+            "{:?} {}{:?}",
+            message.command,
+            message
+                .prefix
+                .clone()
+                .map(|p| format!("from {:?} ", p))
+                .unwrap_or("".to_owned()),
+            ctx.tags,
+        );
+        EventAction::Continue
+    }
 }
 
 fn tags_to_map(tags: Option<Vec<Tag>>) -> BTreeMap<String, String> {
@@ -1,56 +1,54 @@
 mod config;
+mod discord;
 mod handlers;
+mod metrics;
+
+use std::time::Duration;
 
 use futures_util::StreamExt;
 use irc::{
-    client::{data::config::Config, Client},
-    proto::{Capability, Command, Message},
+    client::{data::config::Config, Client, ClientStream},
+    proto::{Capability, Command, Response},
 };
 use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::sleep;
 
-use crate::handlers::{
-    handle_clear_chat, handle_clear_msg, handle_notice, handle_priv_msg, print_message,
-};
+use crate::discord::DiscordBridge;
+use crate::handlers::{EventAction, HandlerRegistry};
+use crate::metrics::Metrics;
 use config::{load_config, FileHandleManager};
 
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const METRICS_ADDR: &str = "0.0.0.0:9090";
+
 #[tokio::main]
 async fn main() {
-    let (mut irc_channels, mut file_handles) = load_config().await;
+    let metrics = Metrics::new();
+    tokio::spawn(metrics::serve(
+        METRICS_ADDR.parse().expect("valid metrics listen address"),
+    ));
 
-    let mut sighup_stream = signal(SignalKind::hangup()).expect("create sighup stream");
-
-    let irc_config = Config {
-        nickname: Some("justinfan12345".to_owned()),
-        server: Some("irc.chat.twitch.tv".to_owned()),
-        use_tls: Some(true),
-        channels: irc_channels.clone(),
-        ..Default::default()
-    };
+    let (mut irc_channels, mut file_handles, mut discord_webhooks) = load_config(&metrics).await;
+    let discord_bridge = DiscordBridge::spawn();
+    let handler_registry = HandlerRegistry::new();
 
-    let mut irc_client = Client::from_config(irc_config)
-        .await
-        .expect("valid IRC client config");
-
-    irc_client
-        .send_cap_req(&[
-            Capability::Custom("twitch.tv/tags"),
-            Capability::Custom("twitch.tv/commands"),
-        ])
-        .expect("send capability request");
-    irc_client.identify().expect("IRC identify");
+    let mut sighup_stream = signal(SignalKind::hangup()).expect("create sighup stream");
 
-    let mut irc_stream = irc_client.stream().expect("IRC stream");
+    let (mut irc_client, mut irc_stream) = connect(&irc_channels).await;
+    let mut backoff = INITIAL_BACKOFF;
 
     loop {
         tokio::select! {
             _ = sighup_stream.recv() => {
-                let (new_irc_channels, new_file_handles) = load_config().await;
+                let (new_irc_channels, new_file_handles, new_discord_webhooks) = load_config(&metrics).await;
                 let removed_irc_channels: Vec<_> = irc_channels.clone().into_iter().filter(|c| !new_irc_channels.contains(c)).collect();
                 let added_irc_channels: Vec<_> = new_irc_channels.clone().into_iter().filter(|c| !irc_channels.contains(c)).collect();
                 if !removed_irc_channels.is_empty() {
                     irc_client.send_part(removed_irc_channels.join(",")).expect("leave removed chanels");
                 }
                 file_handles = new_file_handles;
+                discord_webhooks = new_discord_webhooks;
                 if !added_irc_channels.is_empty() {
                     irc_client.send_join(added_irc_channels.join(",")).expect("join added channels");
                 }
@@ -58,45 +56,111 @@ async fn main() {
                 println!("Reloaded config.");
             }
             irc_message = irc_stream.next() => {
-                match irc_message {
-                    Some(irc_event) => handle_irc_event(&mut file_handles, irc_event).await,
-                    _ => break
+                let action = match irc_message {
+                    Some(Ok(message)) => {
+                        if is_welcome(&message.command) {
+                            backoff = INITIAL_BACKOFF;
+                        }
+                        handler_registry
+                            .dispatch(&mut file_handles, &discord_webhooks, &discord_bridge, &metrics, &message)
+                            .await
+                    }
+                    Some(Err(err)) => {
+                        eprintln!("IRC stream error: {}", err);
+                        EventAction::Reconnect
+                    }
+                    None => {
+                        eprintln!("IRC stream ended");
+                        EventAction::Reconnect
+                    }
+                };
+
+                if let EventAction::Reconnect = action {
+                    let (new_irc_client, new_irc_stream) =
+                        reconnect(&irc_channels, &mut file_handles, &metrics, &mut backoff).await;
+                    irc_client = new_irc_client;
+                    irc_stream = new_irc_stream;
                 }
             }
-            else => break
         }
     }
 }
 
-async fn handle_irc_event(
+/// Connects, requests Twitch capabilities, and identifies. Used for the
+/// initial connection, where a bad config should fail fast.
+async fn connect(irc_channels: &[String]) -> (Client, ClientStream) {
+    try_connect(irc_channels)
+        .await
+        .expect("valid IRC client config")
+}
+
+/// Fallible version of `connect` used by the reconnect loop, where a
+/// connection failure (the exact scenario this subsystem exists for) must be
+/// retried rather than crash the process.
+async fn try_connect(irc_channels: &[String]) -> Result<(Client, ClientStream), irc::error::Error> {
+    let irc_config = Config {
+        nickname: Some("justinfan12345".to_owned()),
+        server: Some("irc.chat.twitch.tv".to_owned()),
+        use_tls: Some(true),
+        channels: irc_channels.to_vec(),
+        ..Default::default()
+    };
+
+    let mut irc_client = Client::from_config(irc_config).await?;
+
+    irc_client.send_cap_req(&[
+        Capability::Custom("twitch.tv/tags"),
+        Capability::Custom("twitch.tv/commands"),
+    ])?;
+    irc_client.identify()?;
+
+    let irc_stream = irc_client.stream()?;
+
+    Ok((irc_client, irc_stream))
+}
+
+/// Connects and re-JOINs every channel, failing if either step fails. Used
+/// by the reconnect loop, where a JOIN failure right after connecting is
+/// just as much a transient failure as the connect itself.
+async fn try_reconnect(irc_channels: &[String]) -> Result<(Client, ClientStream), irc::error::Error> {
+    let (irc_client, irc_stream) = try_connect(irc_channels).await?;
+    irc_client.send_join(irc_channels.join(","))?;
+    Ok((irc_client, irc_stream))
+}
+
+/// Sleeps for the current backoff (doubling it, capped at `MAX_BACKOFF`),
+/// retrying the connection attempt itself on failure, then re-JOINs every
+/// channel we were logging and leaves a marker in each channel's log so
+/// consumers can see where the gap is. A JOIN failure (the connection
+/// dropping again right after it's established) is treated the same as a
+/// failed connect attempt and retried rather than panicking.
+async fn reconnect(
+    irc_channels: &[String],
     file_handles: &mut FileHandleManager,
-    irc_event: Result<Message, irc::error::Error>,
-) {
-    let message = irc_event.expect("get IRC message");
-    match message.clone().command {
-        Command::PRIVMSG(ref channel_name, ref msg) => {
-            handle_priv_msg(
-                file_handles,
-                message.source_nickname().unwrap_or("???"),
-                channel_name,
-                msg,
-                message.tags.clone(),
-            )
-            .await
-        }
+    metrics: &Metrics,
+    backoff: &mut Duration,
+) -> (Client, ClientStream) {
+    let (irc_client, irc_stream) = loop {
+        eprintln!("Reconnecting in {:?}...", backoff);
+        sleep(*backoff).await;
+        *backoff = (*backoff * 2).min(MAX_BACKOFF);
 
-        Command::Raw(command, value) => match command.as_str() {
-            "CLEARCHAT" => handle_clear_chat(file_handles, &value, message.tags).await,
-            "CLEARMSG" => handle_clear_msg(file_handles, &value, message.tags).await,
-            "ROOMSTATE" | "USERNOTICE" => {
-                handle_notice(file_handles, &command, &value, message.tags).await
-            }
-            _ => {
-                print_message(&message);
-            }
-        },
-        _ => {
-            print_message(&message);
+        match try_reconnect(irc_channels).await {
+            Ok(connected) => break connected,
+            Err(err) => eprintln!("Reconnect attempt failed: {}", err),
         }
+    };
+
+    let marker = format!("// Reconnected at {}", chrono::Local::now().to_rfc3339());
+    for channel in irc_channels {
+        file_handles.write_marker(channel, marker.clone()).await;
     }
+
+    metrics.reconnects.inc();
+
+    (irc_client, irc_stream)
+}
+
+fn is_welcome(command: &Command) -> bool {
+    matches!(command, Command::Response(Response::RPL_WELCOME, _))
 }
@@ -0,0 +1,93 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use prometheus::{
+    register_int_counter, register_int_counter_vec, register_int_gauge, IntCounter,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+
+/// Metrics exposed on `/metrics` so an operator running `purple-ghost` as a
+/// long-lived logger can alert on a stalled connection (no message
+/// increments) or runaway log growth.
+#[derive(Clone)]
+pub(crate) struct Metrics {
+    pub(crate) messages_logged: IntCounterVec,
+    pub(crate) clear_chats: IntCounterVec,
+    pub(crate) clear_msgs: IntCounterVec,
+    pub(crate) timeouts: IntCounterVec,
+    pub(crate) bytes_written: IntCounterVec,
+    pub(crate) connected_channels: IntGauge,
+    pub(crate) reconnects: IntCounter,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Metrics {
+        Metrics {
+            messages_logged: register_int_counter_vec!(
+                "purple_ghost_messages_logged_total",
+                "Number of PRIVMSGs logged, per channel",
+                &["channel"]
+            )
+            .expect("register messages_logged metric"),
+            clear_chats: register_int_counter_vec!(
+                "purple_ghost_clear_chats_total",
+                "Number of CLEARCHAT events handled, per channel",
+                &["channel"]
+            )
+            .expect("register clear_chats metric"),
+            clear_msgs: register_int_counter_vec!(
+                "purple_ghost_clear_msgs_total",
+                "Number of CLEARMSG events handled, per channel",
+                &["channel"]
+            )
+            .expect("register clear_msgs metric"),
+            timeouts: register_int_counter_vec!(
+                "purple_ghost_timeouts_total",
+                "Number of user timeouts/bans (CLEARCHAT naming a user) handled, per channel",
+                &["channel"]
+            )
+            .expect("register timeouts metric"),
+            bytes_written: register_int_counter_vec!(
+                "purple_ghost_log_bytes_written_total",
+                "Bytes written to each channel's log file",
+                &["channel"]
+            )
+            .expect("register bytes_written metric"),
+            connected_channels: register_int_gauge!(
+                "purple_ghost_connected_channels",
+                "Number of channels currently being logged"
+            )
+            .expect("register connected_channels metric"),
+            reconnects: register_int_counter!(
+                "purple_ghost_reconnects_total",
+                "Number of times the IRC connection has been re-established"
+            )
+            .expect("register reconnects metric"),
+        }
+    }
+}
+
+/// Serves the Prometheus text exposition format on `/metrics` until the
+/// process exits. Intended to be run as its own tokio task.
+pub(crate) async fn serve(addr: SocketAddr) {
+    let make_svc =
+        make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(serve_metrics)) });
+
+    if let Err(err) = Server::bind(&addr).serve(make_svc).await {
+        eprintln!("metrics server error: {}", err);
+    }
+}
+
+async fn serve_metrics(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let encoder = TextEncoder::new();
+    let metric_families = prometheus::gather();
+
+    let mut buffer = Vec::new();
+    encoder
+        .encode(&metric_families, &mut buffer)
+        .expect("encode metrics");
+
+    Ok(Response::new(Body::from(buffer)))
+}